@@ -20,6 +20,23 @@ pub struct Env {
     /// Print the script to change Node versions every directory change
     #[structopt(long)]
     use_on_cd: bool,
+    /// Canonicalize the exported `FNM_DIR` (following symlinks) before printing
+    /// it, so a symlinked base dir is reported as its real path. This does NOT
+    /// affect `FNM_MULTISHELL_PATH` or the `PATH` entry (and therefore not
+    /// `process.execPath`), which stay symlinked so `fnm use` can keep
+    /// rewriting them. Off by default, as some users rely on the symlinked path.
+    #[structopt(long)]
+    resolve_symlinks: bool,
+}
+
+/// Canonicalize `path` when `resolve` is set, falling back to the original
+/// path if it cannot be resolved (e.g. it does not exist yet).
+fn maybe_canonicalize(path: &std::path::Path, resolve: bool) -> std::path::PathBuf {
+    if resolve {
+        std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    } else {
+        path.to_path_buf()
+    }
 }
 
 fn generate_symlink_path(root: &std::path::Path) -> std::path::PathBuf {
@@ -31,7 +48,146 @@ fn generate_symlink_path(root: &std::path::Path) -> std::path::PathBuf {
     root.join(temp_dir_name)
 }
 
-fn make_symlink(config: &FnmConfig) -> std::path::PathBuf {
+#[cfg(unix)]
+fn link_default_version_dir(
+    config: &FnmConfig,
+    path: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    if path.exists() {
+        std::fs::remove_dir(path).ok();
+    }
+    symlink_dir(config.default_version_dir(), path)
+}
+
+/// `ERROR_PRIVILEGE_NOT_HELD` — raised when the user lacks the
+/// `SeCreateSymbolicLink` privilege (no admin / Developer Mode off).
+#[cfg(windows)]
+const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+#[cfg(windows)]
+fn link_default_version_dir(
+    config: &FnmConfig,
+    path: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    let source = config.default_version_dir();
+    if path.exists() {
+        std::fs::remove_dir(path).ok();
+    }
+    match std::os::windows::fs::symlink_dir(&source, path) {
+        Err(err) if err.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD) => {
+            // Without the symlink privilege we can still create an NTFS
+            // directory junction, which needs no elevation.
+            let output = std::process::Command::new("cmd")
+                .arg("/C")
+                .arg("mklink")
+                .arg("/J")
+                .arg(path)
+                .arg(&source)
+                .output()?;
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("mklink /J failed: {}", String::from_utf8_lossy(&output.stderr).trim()),
+                ))
+            }
+        }
+        other => other,
+    }
+}
+
+#[cfg(unix)]
+fn is_process_alive(pid: u32) -> bool {
+    // `kill(pid, 0)` performs error checking without sending a signal:
+    // it succeeds while the process exists and fails with `ESRCH` otherwise.
+    // `EPERM` means the process exists but belongs to another user.
+    unsafe {
+        libc::kill(pid as libc::pid_t, 0) == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+#[cfg(windows)]
+fn is_process_alive(pid: u32) -> bool {
+    use std::os::windows::raw::HANDLE;
+
+    const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+    const STILL_ACTIVE: u32 = 259;
+
+    extern "system" {
+        fn OpenProcess(access: u32, inherit: i32, pid: u32) -> HANDLE;
+        fn GetExitCodeProcess(handle: HANDLE, code: *mut u32) -> i32;
+        fn CloseHandle(handle: HANDLE) -> i32;
+    }
+
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return false;
+        }
+        let mut code: u32 = 0;
+        let alive = GetExitCodeProcess(handle, &mut code) != 0 && code == STILL_ACTIVE;
+        CloseHandle(handle);
+        alive
+    }
+}
+
+/// Extract the PID embedded in a `fnm_multishell_{pid}_{timestamp}` name,
+/// returning `None` when the prefix is missing or the PID is not a number.
+/// The timestamp part is not checked.
+fn parse_multishell_pid(name: &str) -> Option<u32> {
+    name.strip_prefix("fnm_multishell_")
+        .and_then(|rest| rest.split('_').next())
+        .and_then(|pid| pid.parse::<u32>().ok())
+}
+
+/// Remove `fnm_multishell_*` symlinks left behind by shells that have since
+/// exited. The PID embedded in each name is checked against the running
+/// processes and only dead ones are reaped, so concurrently-active shells
+/// keep their links.
+fn remove_stale_symlinks() {
+    let system_temp_dir = std::env::temp_dir();
+    let entries = match std::fs::read_dir(&system_temp_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let current_pid = std::process::id();
+
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let name = match file_name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        let pid = match parse_multishell_pid(name) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // Never reap the link belonging to us, and leave links owned by a
+        // still-running shell alone.
+        if pid == current_pid || is_process_alive(pid) {
+            continue;
+        }
+        // Tolerate races where another fnm reaps the same entry first.
+        remove_multishell_symlink(&entry.path());
+    }
+}
+
+/// Remove a `fnm_multishell_*` symlink. These point at a directory, so on
+/// Windows they are directory symlinks/junctions that `remove_file` refuses —
+/// use `remove_dir` there, mirroring `link_default_version_dir`.
+#[cfg(windows)]
+fn remove_multishell_symlink(path: &std::path::Path) {
+    std::fs::remove_dir(path).ok();
+}
+
+#[cfg(unix)]
+fn remove_multishell_symlink(path: &std::path::Path) {
+    std::fs::remove_file(path).ok();
+}
+
+fn make_symlink(config: &FnmConfig) -> Result<std::path::PathBuf, Error> {
     let system_temp_dir = std::env::temp_dir();
     let mut temp_dir = generate_symlink_path(&system_temp_dir);
 
@@ -39,8 +195,10 @@ fn make_symlink(config: &FnmConfig) -> std::path::PathBuf {
         temp_dir = generate_symlink_path(&system_temp_dir);
     }
 
-    symlink_dir(config.default_version_dir(), &temp_dir).expect("Can't create symlink!");
-    temp_dir
+    match link_default_version_dir(config, &temp_dir) {
+        Ok(()) => Ok(temp_dir),
+        Err(source) => Err(Error::CantCreateSymlink { source }),
+    }
 }
 
 impl Command for Env {
@@ -52,12 +210,18 @@ impl Command for Env {
         }
 
         let shell: Box<dyn Shell> = self.shell.or_else(&infer_shell).context(CantInferShell)?;
-        let multishell_path = make_symlink(&config);
+        remove_stale_symlinks();
+        // The multishell path must stay the per-shell symlink that `fnm use`
+        // rewrites, so it is never canonicalized — resolving it would point at
+        // `default_version_dir` and freeze version switching. Only the base dir
+        // exported as `FNM_DIR` is resolved under `--resolve-symlinks`.
+        let multishell_path = make_symlink(&config)?;
         let binary_path = if cfg!(windows) {
             multishell_path.clone()
         } else {
             multishell_path.join("bin")
         };
+        let base_dir = maybe_canonicalize(&config.base_dir_with_default(), self.resolve_symlinks);
         println!("{}", shell.path(&binary_path));
         println!(
             "{}",
@@ -65,7 +229,7 @@ impl Command for Env {
         );
         println!(
             "{}",
-            shell.set_env_var("FNM_DIR", config.base_dir_with_default().to_str().unwrap())
+            shell.set_env_var("FNM_DIR", base_dir.to_str().unwrap())
         );
         println!(
             "{}",
@@ -92,6 +256,8 @@ pub enum Error {
         shells_as_string()
     ))]
     CantInferShell,
+    #[snafu(display("Can't create symlink: {}", source))]
+    CantCreateSymlink { source: std::io::Error },
 }
 
 fn shells_as_string() -> String {
@@ -121,4 +287,12 @@ mod tests {
         }
         .call(config);
     }
+
+    #[test]
+    fn test_parse_multishell_pid() {
+        assert_eq!(parse_multishell_pid("fnm_multishell_1234_5678"), Some(1234));
+        assert_eq!(parse_multishell_pid("fnm_multishell_42_0"), Some(42));
+        assert_eq!(parse_multishell_pid("some_other_file"), None);
+        assert_eq!(parse_multishell_pid("fnm_multishell_notapid_0"), None);
+    }
 }